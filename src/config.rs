@@ -7,6 +7,10 @@ pub struct Config {
     pub network: NetworkConfig,
     pub template: TemplateConfig,
     pub service: ServiceConfig,
+    #[serde(default)]
+    pub log: LogConfig,
+    #[serde(default)]
+    pub access: AccessConfig,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -17,8 +21,7 @@ pub struct NetworkConfig {
 
 #[derive(Serialize, Deserialize)]
 pub struct TemplateConfig {
-    pub header_file: PathBuf,
-    pub footer_file: PathBuf,
+    pub index_file: PathBuf,
     pub error_file: PathBuf,
 }
 
@@ -27,3 +30,21 @@ pub struct ServiceConfig {
     pub limit: u64,
     pub root: PathBuf,
 }
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LogConfig {
+    pub access_log_file: Option<PathBuf>,
+    pub error_log_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AccessConfig {
+    #[serde(default)]
+    pub hide_dotfiles: bool,
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+    #[serde(default)]
+    pub shared_secret: Option<String>,
+}
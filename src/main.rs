@@ -1,16 +1,36 @@
+use std::{fs::OpenOptions, path::Path, sync::Arc};
+
+use arc_swap::ArcSwap;
 use clap::Parser;
 use cmdline::Cmdline;
-use config::Config;
+use config::{Config, LogConfig};
 use figment::providers::{Format, Toml};
-use server::root;
-use tracing_subscriber::{filter::EnvFilter, layer::SubscriberExt, util::SubscriberInitExt, Layer};
+use server::{App, Template};
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+use tracing_subscriber::{
+    filter::{EnvFilter, LevelFilter, Targets},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    Layer,
+};
 
 mod cmdline;
 mod config;
 mod server;
+mod watch;
+
+struct LogGuards {
+    _access: Option<WorkerGuard>,
+    _error: Option<WorkerGuard>,
+}
 
-fn init_logging() {
-    let console_subscriber = tracing_subscriber::fmt::layer()
+fn open_appender(path: &Path) -> std::io::Result<(NonBlocking, WorkerGuard)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(tracing_appender::non_blocking(file))
+}
+
+fn init_logging(log: &LogConfig) -> LogGuards {
+    let console_layer = tracing_subscriber::fmt::layer()
         .with_writer(std::io::stderr)
         .with_file(true)
         .with_thread_names(true)
@@ -18,25 +38,72 @@ fn init_logging() {
         .with_target(false)
         .with_ansi(true)
         .with_filter(EnvFilter::from_env("YADEX_LOGLEVEL"));
+
+    let (access_layer, access_guard) = match log.access_log_file.as_deref().map(open_appender) {
+        Some(Ok((writer, guard))) => (
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .with_writer(writer)
+                    .with_ansi(false)
+                    .with_target(false)
+                    .with_filter(Targets::new().with_target("yadex::access", LevelFilter::INFO)),
+            ),
+            Some(guard),
+        ),
+        Some(Err(err)) => {
+            eprintln!("failed to open access log file: {err}");
+            (None, None)
+        }
+        None => (None, None),
+    };
+
+    let (error_layer, error_guard) = match log.error_log_file.as_deref().map(open_appender) {
+        Some(Ok((writer, guard))) => (
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .with_writer(writer)
+                    .with_ansi(false)
+                    .with_filter(LevelFilter::ERROR),
+            ),
+            Some(guard),
+        ),
+        Some(Err(err)) => {
+            eprintln!("failed to open error log file: {err}");
+            (None, None)
+        }
+        None => (None, None),
+    };
+
     tracing_subscriber::registry()
-        .with(console_subscriber)
+        .with(console_layer)
+        .with(access_layer)
+        .with(error_layer)
         .init();
+
+    LogGuards {
+        _access: access_guard,
+        _error: error_guard,
+    }
 }
 
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
-    init_logging();
     color_eyre::install()?;
     let cmdline = Cmdline::parse();
-    tracing::info!("cmdline: {:?}", cmdline);
     let config: Config = figment::Figment::new()
-        .merge(Toml::file(cmdline.config))
+        .merge(Toml::file(&cmdline.config))
         .extract()?;
-    let app = Router::new().route("/", get(root));
+    let _log_guards = init_logging(&config.log);
+    tracing::info!("cmdline: {:?}", cmdline);
+    let template = Template::from_config(&cmdline.config, &config.template)?;
+    let template = Arc::new(ArcSwap::new(Arc::new(template)));
+    let _watcher = watch::watch_templates(cmdline.config.clone(), template.clone())
+        .inspect_err(|err| tracing::error!("failed to start template watcher: {err}"))
+        .ok();
 
     let listener =
         tokio::net::TcpListener::bind((config.network.address, config.network.port)).await?;
     tracing::info!("Yadex listening on {}", listener.local_addr()?);
-    axum::serve(listener, app).await?;
+    App::serve(config.service, listener, template, config.access).await?;
     Ok(())
 }
@@ -1,28 +1,41 @@
 use std::{
     env::set_current_dir,
     fs, io,
+    net::SocketAddr,
     os::unix::fs::{chroot, MetadataExt},
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 
+use arc_swap::ArcSwap;
 use axum::{
-    extract::State,
-    http::Uri,
+    body::Body,
+    extract::{ConnectInfo, Query, Request, State},
+    http::{header, HeaderMap, StatusCode, Uri},
+    middleware::{self, Next},
     response::{Html, IntoResponse, Redirect, Response},
     routing::get,
-    Router,
+    Json, Router,
 };
 use chrono::Utc;
 use futures_util::StreamExt as SExt;
 use handlebars::RenderError;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use snafu::{ResultExt, Snafu};
-use tokio::{fs::DirEntry, net::TcpListener};
+use subtle::ConstantTimeEq;
+use tokio::{
+    fs::DirEntry,
+    io::{AsyncReadExt, AsyncSeekExt},
+    net::TcpListener,
+};
 use tokio_stream::wrappers::ReadDirStream;
+use tokio_util::io::ReaderStream;
 use tracing::error;
 
-use crate::config::{ServiceConfig, TemplateConfig};
+use crate::config::{AccessConfig, ServiceConfig, TemplateConfig};
 
 pub struct App {}
 
@@ -47,11 +60,11 @@ pub enum TemplateLoadError {
 impl Template {
     pub fn from_config(
         path_to_config: &Path,
-        config: TemplateConfig,
+        config: &TemplateConfig,
     ) -> Result<Self, TemplateLoadError> {
         let mut registry = handlebars::Handlebars::new();
         let config_dir = path_to_config.parent().unwrap();
-        let index_path = config_dir.join(config.index_file);
+        let index_path = config_dir.join(&config.index_file);
         let index = std::fs::read_to_string(&index_path).context(IoSnafu {
             component: "index",
             path: index_path,
@@ -59,7 +72,7 @@ impl Template {
         registry
             .register_template_string("index", index)
             .context(RegisterSnafu { component: "index" })?;
-        let error_path = config_dir.join(config.error_file);
+        let error_path = config_dir.join(&config.error_file);
         let error = std::fs::read_to_string(&error_path).context(IoSnafu {
             component: "error",
             path: error_path,
@@ -82,31 +95,130 @@ impl App {
     pub async fn serve(
         config: ServiceConfig,
         listener: TcpListener,
-        template: Template,
+        template: SharedTemplate,
+        access: AccessConfig,
     ) -> Result<(), YadexError> {
         let router = Router::new()
+            .route("/.yadex/healthz", get(healthz))
+            .route("/.yadex/stats", get(stats))
             .fallback(get(directory_listing))
+            .layer(middleware::from_fn(access_log))
             .with_state(AppState {
                 limit: if config.limit == 0 {
                     usize::MAX
                 } else {
                     config.limit as usize
                 },
-                template: Arc::new(template),
+                configured_limit: config.limit,
+                template,
+                access: Arc::new(access),
+                stats: Arc::new(Stats::default()),
             });
         let root: &'static Path = Box::leak(Box::<Path>::from(config.root));
         chroot(root).whatever_context("failed to chroot")?;
         set_current_dir("/").whatever_context("failed to cd into new root")?;
-        axum::serve(listener, router)
-            .await
-            .with_whatever_context(|_| "serve failed")
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .with_whatever_context(|_| "serve failed")
+    }
+}
+
+async fn access_log(
+    State(state): State<AppState>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    let start = std::time::Instant::now();
+    let response = next.run(request).await;
+    let duration_ms = start.elapsed().as_millis();
+    let bytes = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .or_else(|| http_body::Body::size_hint(response.body()).exact());
+    let status = response.status().as_u16();
+    let size = bytes.map_or("-".to_string(), |b| b.to_string());
+
+    state.stats.requests.fetch_add(1, Ordering::Relaxed);
+    if let Some(bytes) = bytes {
+        state.stats.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    tracing::info!(
+        target: "yadex::access",
+        "{client} \"{method} {path}\" {status} {size} {duration_ms}ms"
+    );
+    response
+}
+
+async fn healthz() -> &'static str {
+    "OK"
+}
+
+#[derive(Debug, Serialize)]
+struct StatsResponse {
+    uptime_secs: u64,
+    requests_served: u64,
+    bytes_sent: u64,
+    limit: u64,
+}
+
+#[derive(Debug)]
+struct Stats {
+    start: std::time::Instant,
+    requests: AtomicU64,
+    bytes_sent: AtomicU64,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            start: std::time::Instant::now(),
+            requests: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+        }
     }
 }
 
+impl Stats {
+    fn uptime(&self) -> std::time::Duration {
+        self.start.elapsed()
+    }
+}
+
+async fn stats(
+    State(state): State<AppState>,
+    Query(query): Query<TokenQuery>,
+    headers: HeaderMap,
+) -> Result<Json<StatsResponse>, YadexError> {
+    let token = token_from_request(query.token.as_deref(), &headers);
+    if !secret_satisfied(&state.access, token) {
+        return Err(YadexError::Forbidden);
+    }
+    Ok(Json(StatsResponse {
+        uptime_secs: state.stats.uptime().as_secs(),
+        requests_served: state.stats.requests.load(Ordering::Relaxed),
+        bytes_sent: state.stats.bytes_sent.load(Ordering::Relaxed),
+        limit: state.configured_limit,
+    }))
+}
+
+pub type SharedTemplate = Arc<ArcSwap<Template>>;
+
 #[derive(Clone)]
 pub struct AppState {
     limit: usize,
-    template: Arc<Template>,
+    configured_limit: u64,
+    template: SharedTemplate,
+    access: Arc<AccessConfig>,
+    stats: Arc<Stats>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -128,30 +240,216 @@ pub async fn direntry_info(val: Result<DirEntry, io::Error>) -> Option<(DirEntry
 struct IndexData<'a> {
     entry: &'a [DirEntryInfo],
     maybe_truncated: bool,
+    sort: &'static str,
+    order: &'static str,
+    breadcrumbs: Vec<Breadcrumb>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Breadcrumb {
+    name: String,
+    href: String,
+}
+
+fn breadcrumbs(path: &str) -> Vec<Breadcrumb> {
+    let mut href = String::from("/");
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| {
+            href.push_str(segment);
+            href.push('/');
+            let name = percent_encoding::percent_decode_str(segment)
+                .decode_utf8()
+                .map(|decoded| decoded.into_owned())
+                .unwrap_or_else(|_| segment.to_owned());
+            Breadcrumb {
+                name,
+                href: href.clone(),
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortKey {
+    Name,
+    Size,
+    Mtime,
+}
+
+impl SortKey {
+    fn parse(sort: Option<&str>) -> Self {
+        match sort {
+            Some("size") => Self::Size,
+            Some("mtime") => Self::Mtime,
+            _ => Self::Name,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::Size => "size",
+            Self::Mtime => "mtime",
+        }
+    }
+
+    fn compare(self, a: &DirEntryInfo, b: &DirEntryInfo) -> std::cmp::Ordering {
+        match self {
+            Self::Name => a.name.cmp(&b.name),
+            Self::Size => a.size.cmp(&b.size),
+            Self::Mtime => a.datetime.cmp(&b.datetime),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    fn parse(order: Option<&str>) -> Self {
+        match order {
+            Some("desc") => Self::Desc,
+            _ => Self::Asc,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Asc => "asc",
+            Self::Desc => "desc",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ListingQuery {
+    sort: Option<String>,
+    order: Option<String>,
+    token: Option<String>,
+}
+
+fn path_is_denied(access: &AccessConfig, path: &str) -> bool {
+    let path = normalize_policy_path(path);
+    if access.hide_dotfiles
+        && path
+            .split('/')
+            .any(|segment| !segment.is_empty() && segment.starts_with('.'))
+    {
+        return true;
+    }
+    if access
+        .deny
+        .iter()
+        .any(|pattern| glob_matches(pattern, path))
+    {
+        return true;
+    }
+    !access.allow.is_empty()
+        && !access
+            .allow
+            .iter()
+            .any(|pattern| glob_matches(pattern, path))
+}
+
+// So a directory is denied the same way whether it's checked with or
+// without its trailing slash (e.g. a bare `GET /secret/sub/` vs. the
+// slash-less form built for entries in a listing).
+fn normalize_policy_path(path: &str) -> &str {
+    if path.len() > 1 {
+        path.trim_end_matches('/')
+    } else {
+        path
+    }
+}
+
+fn glob_matches(pattern: &str, path: &str) -> bool {
+    let pattern = normalize_policy_path(pattern);
+    glob::Pattern::new(pattern)
+        .map(|compiled| compiled.matches(path))
+        .unwrap_or(false)
+}
+
+fn secret_satisfied(access: &AccessConfig, token: Option<&str>) -> bool {
+    match &access.shared_secret {
+        Some(secret) => token
+            .map(|token| token.as_bytes().ct_eq(secret.as_bytes()).into())
+            .unwrap_or(false),
+        None => true,
+    }
+}
+
+fn token_from_request<'a>(query_token: Option<&'a str>, headers: &'a HeaderMap) -> Option<&'a str> {
+    query_token.or_else(|| headers.get("x-yadex-token").and_then(|v| v.to_str().ok()))
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
 }
 
 #[axum::debug_handler]
 pub async fn directory_listing(
     State(state): State<AppState>,
+    Query(query): Query<ListingQuery>,
     uri: Uri,
+    headers: HeaderMap,
 ) -> Result<Response, YadexError> {
-    let path = uri.path();
+    let raw_path = uri.path();
+    let path = percent_encoding::percent_decode_str(raw_path)
+        .decode_utf8()
+        .map(|decoded| decoded.into_owned())
+        .unwrap_or_else(|_| raw_path.to_owned());
+    let path = path.as_str();
 
-    if !path.ends_with('/') {
-        return Ok(Redirect::permanent(&format!("{path}/")).into_response());
+    let token = token_from_request(query.token.as_deref(), &headers);
+    if path_is_denied(&state.access, path) || !secret_satisfied(&state.access, token) {
+        return Err(YadexError::Forbidden);
     }
 
-    let entries = ReadDirStream::new(tokio::fs::read_dir(path).await.context(NotFoundSnafu)?)
+    let meta = tokio::fs::metadata(path).await.context(NotFoundSnafu)?;
+
+    if !meta.is_dir() {
+        let cache = CacheValidators::from_metadata(&meta);
+        if cache.is_not_modified(&headers) {
+            return Ok(cache.not_modified_response());
+        }
+        return serve_file(path, meta, headers, cache).await;
+    }
+
+    if !raw_path.ends_with('/') {
+        let location = match uri.query() {
+            Some(query) => format!("{raw_path}/?{query}"),
+            None => format!("{raw_path}/"),
+        };
+        return Ok(Redirect::permanent(&location).into_response());
+    }
+
+    let cache = CacheValidators::from_metadata(&meta);
+    if cache.is_not_modified(&headers) {
+        return Ok(cache.not_modified_response());
+    }
+
+    let sort = SortKey::parse(query.sort.as_deref());
+    let order = SortOrder::parse(query.order.as_deref());
+
+    let mut entries = ReadDirStream::new(tokio::fs::read_dir(path).await.context(NotFoundSnafu)?)
         .take(state.limit)
         .filter_map(async |entry| match direntry_info(entry).await {
             Some((d, meta)) => {
                 let name = d.file_name();
                 let name = name.to_string_lossy();
+                if path_is_denied(&state.access, &format!("{path}{name}")) {
+                    return None;
+                }
                 Some(DirEntryInfo {
                     is_dir: meta.is_dir(),
                     size: meta.size(),
                     href: format!(
-                        "{path}{file}{slash}",
+                        "{raw_path}{file}{slash}",
                         file = html_escape::encode_double_quoted_attribute(&urlencoding::encode(
                             &name
                         )),
@@ -165,17 +463,184 @@ pub async fn directory_listing(
         })
         .collect::<Vec<_>>()
         .await;
+    entries.sort_by(|a, b| {
+        a.is_dir.cmp(&b.is_dir).reverse().then_with(|| match order {
+            SortOrder::Asc => sort.compare(a, b),
+            SortOrder::Desc => sort.compare(a, b).reverse(),
+        })
+    });
     let html = state
         .template
+        .load()
         .render(
             "index",
             &IndexData {
                 entry: &entries,
                 maybe_truncated: entries.len() == state.limit,
+                sort: sort.as_str(),
+                order: order.as_str(),
+                breadcrumbs: breadcrumbs(raw_path),
             },
         )
         .context(RenderSnafu { template: "index" })?;
-    Ok(Html(html).into_response())
+    let mut response = Html(html).into_response();
+    cache.apply(response.headers_mut());
+    Ok(response)
+}
+
+#[derive(Clone, Copy)]
+struct CacheValidators {
+    last_modified: chrono::DateTime<Utc>,
+    size: u64,
+    mtime: i64,
+}
+
+impl CacheValidators {
+    fn from_metadata(meta: &fs::Metadata) -> Self {
+        Self {
+            last_modified: chrono::DateTime::from_timestamp(meta.mtime(), 0).unwrap_or_default(),
+            size: meta.size(),
+            mtime: meta.mtime(),
+        }
+    }
+
+    fn etag(&self) -> String {
+        format!("W/\"{:x}-{:x}\"", self.size, self.mtime)
+    }
+
+    fn last_modified_header(&self) -> String {
+        self.last_modified
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string()
+    }
+
+    fn apply(&self, headers: &mut axum::http::HeaderMap) {
+        if let Ok(v) = self.etag().parse() {
+            headers.insert(header::ETAG, v);
+        }
+        if let Ok(v) = self.last_modified_header().parse() {
+            headers.insert(header::LAST_MODIFIED, v);
+        }
+    }
+
+    fn is_not_modified(&self, headers: &HeaderMap) -> bool {
+        if let Some(inm) = headers
+            .get(header::IF_NONE_MATCH)
+            .and_then(|v| v.to_str().ok())
+        {
+            let etag = self.etag();
+            return inm
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag == etag);
+        }
+        if let Some(since) = headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| chrono::DateTime::parse_from_rfc2822(v).ok())
+        {
+            return self.last_modified <= since;
+        }
+        false
+    }
+
+    fn not_modified_response(&self) -> Response {
+        let mut response = StatusCode::NOT_MODIFIED.into_response();
+        self.apply(response.headers_mut());
+        response
+    }
+}
+
+enum ByteRange {
+    Satisfiable { start: u64, end: u64 },
+    Unsatisfiable,
+}
+
+fn parse_byte_range(value: &str, len: u64) -> Option<ByteRange> {
+    let spec = value.strip_prefix("bytes=")?;
+    let (start, end) = spec.split_once('-')?;
+    if start.is_empty() {
+        let suffix_len: u64 = end.parse().ok()?;
+        return Some(if suffix_len == 0 || len == 0 {
+            ByteRange::Unsatisfiable
+        } else {
+            ByteRange::Satisfiable {
+                start: len.saturating_sub(suffix_len),
+                end: len - 1,
+            }
+        });
+    }
+    let start: u64 = start.parse().ok()?;
+    if start >= len {
+        return Some(ByteRange::Unsatisfiable);
+    }
+    let end = if end.is_empty() {
+        len - 1
+    } else {
+        end.parse::<u64>().ok()?.min(len - 1)
+    };
+    Some(if start > end {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Satisfiable { start, end }
+    })
+}
+
+async fn serve_file(
+    path: &str,
+    meta: fs::Metadata,
+    headers: HeaderMap,
+    cache: CacheValidators,
+) -> Result<Response, YadexError> {
+    let len = meta.size();
+    let content_type = mime_guess::from_path(path).first_or_octet_stream();
+    let mut file = tokio::fs::File::open(path).await.context(NotFoundSnafu)?;
+
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| parse_byte_range(v, len));
+
+    let mut response = match range {
+        Some(ByteRange::Unsatisfiable) => (
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            [(header::CONTENT_RANGE, format!("bytes */{len}"))],
+        )
+            .into_response(),
+        Some(ByteRange::Satisfiable { start, end }) => {
+            file.seek(io::SeekFrom::Start(start))
+                .await
+                .context(NotFoundSnafu)?;
+            let body_len = end - start + 1;
+            let body = Body::from_stream(ReaderStream::new(file.take(body_len)));
+            (
+                StatusCode::PARTIAL_CONTENT,
+                [
+                    (header::CONTENT_TYPE, content_type.to_string()),
+                    (header::CONTENT_LENGTH, body_len.to_string()),
+                    (header::CONTENT_RANGE, format!("bytes {start}-{end}/{len}")),
+                    (header::ACCEPT_RANGES, "bytes".to_string()),
+                ],
+                body,
+            )
+                .into_response()
+        }
+        None => {
+            let body = Body::from_stream(ReaderStream::new(file));
+            (
+                StatusCode::OK,
+                [
+                    (header::CONTENT_TYPE, content_type.to_string()),
+                    (header::CONTENT_LENGTH, len.to_string()),
+                    (header::ACCEPT_RANGES, "bytes".to_string()),
+                ],
+                body,
+            )
+                .into_response()
+        }
+    };
+    cache.apply(response.headers_mut());
+    Ok(response)
 }
 
 #[derive(Debug, Snafu)]
@@ -193,6 +658,8 @@ pub enum YadexError {
         source: RenderError,
         template: &'static str,
     },
+    #[snafu(display("You are not allowed to access this resource"))]
+    Forbidden,
 }
 
 impl IntoResponse for YadexError {
@@ -207,6 +674,136 @@ impl IntoResponse for YadexError {
                 error!("internal error: {self}, source: {source:?}");
                 "Internal Server Error".into_response()
             }
+            YadexError::Forbidden => (StatusCode::FORBIDDEN, "403 Forbidden").into_response(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_byte_range_closed_range() {
+        assert!(matches!(
+            parse_byte_range("bytes=0-99", 1000),
+            Some(ByteRange::Satisfiable { start: 0, end: 99 })
+        ));
+    }
+
+    #[test]
+    fn parse_byte_range_open_ended() {
+        assert!(matches!(
+            parse_byte_range("bytes=500-", 1000),
+            Some(ByteRange::Satisfiable {
+                start: 500,
+                end: 999
+            })
+        ));
+    }
+
+    #[test]
+    fn parse_byte_range_suffix() {
+        assert!(matches!(
+            parse_byte_range("bytes=-100", 1000),
+            Some(ByteRange::Satisfiable {
+                start: 900,
+                end: 999
+            })
+        ));
+    }
+
+    #[test]
+    fn parse_byte_range_suffix_longer_than_file() {
+        assert!(matches!(
+            parse_byte_range("bytes=-1000", 100),
+            Some(ByteRange::Satisfiable { start: 0, end: 99 })
+        ));
+    }
+
+    #[test]
+    fn parse_byte_range_zero_length_suffix_is_unsatisfiable() {
+        assert!(matches!(
+            parse_byte_range("bytes=-0", 1000),
+            Some(ByteRange::Unsatisfiable)
+        ));
+    }
+
+    #[test]
+    fn parse_byte_range_empty_file_is_unsatisfiable() {
+        assert!(matches!(
+            parse_byte_range("bytes=0-10", 0),
+            Some(ByteRange::Unsatisfiable)
+        ));
+    }
+
+    #[test]
+    fn parse_byte_range_start_past_end_of_file() {
+        assert!(matches!(
+            parse_byte_range("bytes=1000-", 1000),
+            Some(ByteRange::Unsatisfiable)
+        ));
+    }
+
+    #[test]
+    fn parse_byte_range_end_clamped_to_file_length() {
+        assert!(matches!(
+            parse_byte_range("bytes=0-9999", 1000),
+            Some(ByteRange::Satisfiable { start: 0, end: 999 })
+        ));
+    }
+
+    #[test]
+    fn parse_byte_range_rejects_malformed_header() {
+        assert!(parse_byte_range("not a range", 1000).is_none());
+        assert!(parse_byte_range("bytes=abc-def", 1000).is_none());
+    }
+
+    #[test]
+    fn path_is_denied_matches_with_or_without_trailing_slash() {
+        let access = AccessConfig {
+            deny: vec!["/secret/sub".to_owned()],
+            ..Default::default()
+        };
+        assert!(path_is_denied(&access, "/secret/sub"));
+        assert!(path_is_denied(&access, "/secret/sub/"));
+    }
+
+    #[test]
+    fn path_is_denied_allowlist_restricts_to_matching_paths() {
+        let access = AccessConfig {
+            allow: vec!["/public/*".to_owned()],
+            ..Default::default()
+        };
+        assert!(!path_is_denied(&access, "/public/file.txt"));
+        assert!(path_is_denied(&access, "/private/file.txt"));
+    }
+
+    #[test]
+    fn path_is_denied_hides_dotfile_segments() {
+        let access = AccessConfig {
+            hide_dotfiles: true,
+            ..Default::default()
+        };
+        assert!(path_is_denied(&access, "/.git/config"));
+        assert!(!path_is_denied(&access, "/public/file.txt"));
+    }
+
+    #[test]
+    fn secret_satisfied_requires_matching_token() {
+        let access = AccessConfig {
+            shared_secret: Some("hunter2".to_owned()),
+            ..Default::default()
+        };
+        assert!(secret_satisfied(&access, Some("hunter2")));
+        assert!(!secret_satisfied(&access, Some("wrong")));
+        assert!(!secret_satisfied(&access, None));
+    }
+
+    #[test]
+    fn secret_satisfied_with_no_secret_configured_allows_any_token() {
+        let access = AccessConfig::default();
+        assert!(secret_satisfied(&access, None));
+        assert!(secret_satisfied(&access, Some("anything")));
+    }
+}
@@ -0,0 +1,73 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use figment::providers::{Format, Toml};
+use hotwatch::Hotwatch;
+use tracing::error;
+
+use crate::{
+    config::Config,
+    server::{SharedTemplate, Template},
+};
+
+pub fn watch_templates(
+    config_path: PathBuf,
+    templates: SharedTemplate,
+) -> hotwatch::Result<Hotwatch> {
+    let mut hotwatch = Hotwatch::new()?;
+
+    let config_dir = config_path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .to_path_buf();
+    let initial = figment::Figment::new()
+        .merge(Toml::file(&config_path))
+        .extract::<Config>();
+    let watched: Vec<PathBuf> = match &initial {
+        Ok(config) => vec![
+            config_path.clone(),
+            config_dir.join(&config.template.index_file),
+            config_dir.join(&config.template.error_file),
+        ],
+        Err(_) => vec![config_path.clone()],
+    };
+
+    for path in watched {
+        watch_one(&mut hotwatch, path, config_path.clone(), templates.clone())?;
+    }
+
+    Ok(hotwatch)
+}
+
+fn watch_one(
+    hotwatch: &mut Hotwatch,
+    path: PathBuf,
+    config_path: PathBuf,
+    templates: SharedTemplate,
+) -> hotwatch::Result<()> {
+    hotwatch.watch(path, move |event: hotwatch::Event| {
+        if !matches!(
+            event,
+            hotwatch::Event::Write(_) | hotwatch::Event::Create(_)
+        ) {
+            return;
+        }
+        match reload(&config_path) {
+            Ok(template) => {
+                templates.store(Arc::new(template));
+                tracing::info!("reloaded templates from {config_path:?}");
+            }
+            Err(err) => error!("failed to reload templates: {err}"),
+        }
+    })
+}
+
+fn reload(config_path: &Path) -> Result<Template, String> {
+    let config: Config = figment::Figment::new()
+        .merge(Toml::file(config_path))
+        .extract()
+        .map_err(|err| err.to_string())?;
+    Template::from_config(config_path, &config.template).map_err(|err| err.to_string())
+}